@@ -0,0 +1,212 @@
+use bytemuck::{Pod, Zeroable};
+
+/// A command's fixed-size header: a command byte, a flags byte, and a typed
+/// body, mirroring the way [`crate::responses::Response`] pairs a command
+/// byte with a typed body on the way back.
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct Header<T> {
+    pub command: u8,
+    pub flags: u8,
+    pub body: T,
+}
+
+pub trait Command: Pod {
+    const COMMAND: u8;
+    const FLAGS: u8 = 0;
+
+    /// The stream offset this command carries, if it carries one at all.
+    /// Only used for diagnostic tracing, not for the wire format.
+    fn trace_offset(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl<T: Command> Header<T> {
+    pub fn new(body: T) -> Self {
+        Self {
+            command: T::COMMAND,
+            flags: T::FLAGS,
+            body,
+        }
+    }
+}
+
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct ListDirRequest {
+    pub path_len: u16,
+}
+
+impl ListDirRequest {
+    pub fn new(path_len: u16) -> Self {
+        Self { path_len }
+    }
+}
+
+impl Command for ListDirRequest {
+    const COMMAND: u8 = 0x50;
+}
+
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct ReadRequest {
+    pub path_len: u16,
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl ReadRequest {
+    pub fn new(path_len: u16, offset: u32, length: u32) -> Self {
+        Self {
+            path_len,
+            offset,
+            length,
+        }
+    }
+}
+
+impl Command for ReadRequest {
+    const COMMAND: u8 = 0x10;
+
+    fn trace_offset(&self) -> Option<u32> {
+        Some(self.offset)
+    }
+}
+
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct ReadPacingRequest {
+    _padding: [u8; 2],
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl ReadPacingRequest {
+    pub fn new(offset: u32, length: u32) -> Self {
+        Self {
+            _padding: [0; 2],
+            offset,
+            length,
+        }
+    }
+}
+
+impl Command for ReadPacingRequest {
+    const COMMAND: u8 = 0x12;
+    const FLAGS: u8 = 0x01;
+
+    fn trace_offset(&self) -> Option<u32> {
+        Some(self.offset)
+    }
+}
+
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct WriteHeader {
+    pub path_len: u16,
+    pub offset: u32,
+    pub timestamp: u64,
+    pub total_len: u32,
+}
+
+impl WriteHeader {
+    pub fn new(path_len: u16, offset: u32, timestamp: u64, total_len: u32) -> Self {
+        Self {
+            path_len,
+            offset,
+            timestamp,
+            total_len,
+        }
+    }
+}
+
+impl Command for WriteHeader {
+    const COMMAND: u8 = 0x20;
+
+    fn trace_offset(&self) -> Option<u32> {
+        Some(self.offset)
+    }
+}
+
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct WriteData {
+    _padding: [u8; 2],
+    pub offset: u32,
+    pub current_len: u32,
+}
+
+impl WriteData {
+    pub fn new(offset: u32, current_len: u32) -> Self {
+        Self {
+            _padding: [0; 2],
+            offset,
+            current_len,
+        }
+    }
+}
+
+impl Command for WriteData {
+    const COMMAND: u8 = 0x22;
+    const FLAGS: u8 = 0x01;
+
+    fn trace_offset(&self) -> Option<u32> {
+        Some(self.offset)
+    }
+}
+
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct DeleteRequest {
+    pub path_len: u16,
+}
+
+impl DeleteRequest {
+    pub fn new(path_len: u16) -> Self {
+        Self { path_len }
+    }
+}
+
+impl Command for DeleteRequest {
+    const COMMAND: u8 = 0x30;
+}
+
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct MkdirRequest {
+    pub path_len: u16,
+    _reserved: [u8; 4],
+    pub timestamp: u64,
+}
+
+impl MkdirRequest {
+    pub fn new(path_len: u16, timestamp: u64) -> Self {
+        Self {
+            path_len,
+            _reserved: [0; 4],
+            timestamp,
+        }
+    }
+}
+
+impl Command for MkdirRequest {
+    const COMMAND: u8 = 0x40;
+}
+
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct MoveRequest {
+    pub from_len: u16,
+    pub to_len: u16,
+}
+
+impl MoveRequest {
+    pub fn new(from_len: u16, to_len: u16) -> Self {
+        Self { from_len, to_len }
+    }
+}
+
+impl Command for MoveRequest {
+    const COMMAND: u8 = 0x60;
+}
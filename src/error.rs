@@ -0,0 +1,62 @@
+use std::fmt;
+
+/// Errors from talking to the watch's file-transfer service: either the
+/// underlying BLE link failing, or a protocol-level failure signalled by the
+/// firmware's status byte.
+#[derive(Debug)]
+pub enum FtpError {
+    Transport(btleplug::Error),
+    NotFound,
+    NoSpace,
+    BadStatus(i8),
+    UnexpectedCommand { expected: u8, got: u8 },
+    Truncated,
+}
+
+impl FtpError {
+    /// Maps the protocol's status byte to an error, or `Ok(())` on success
+    /// (status `1`). Beyond the codes called out here the firmware doesn't
+    /// document what each negative value means, so anything else is passed
+    /// through as `BadStatus` rather than guessed at.
+    pub(crate) fn check_status(status: i8) -> Result<()> {
+        match status {
+            1 => Ok(()),
+            -1 => Err(Self::NotFound),
+            -5 => Err(Self::NoSpace),
+            other => Err(Self::BadStatus(other)),
+        }
+    }
+}
+
+impl fmt::Display for FtpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(e) => write!(f, "transport error: {e}"),
+            Self::NotFound => write!(f, "file or directory not found"),
+            Self::NoSpace => write!(f, "not enough free space on the watch"),
+            Self::BadStatus(status) => write!(f, "watch reported status {status}"),
+            Self::UnexpectedCommand { expected, got } => write!(
+                f,
+                "expected a response to command {expected:#04x}, got {got:#04x}"
+            ),
+            Self::Truncated => write!(f, "connection closed mid-transfer"),
+        }
+    }
+}
+
+impl std::error::Error for FtpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<btleplug::Error> for FtpError {
+    fn from(e: btleplug::Error) -> Self {
+        Self::Transport(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, FtpError>;
@@ -1,109 +1,163 @@
 use std::time::SystemTime;
 
-use btleplug::api::{Characteristic, Peripheral as _, ValueNotification, WriteType};
 use btleplug::platform::Peripheral;
-use btleplug::Result;
-use futures::stream::BoxStream;
-use futures::StreamExt;
-use uuid::{uuid, Uuid};
 
+mod commands;
+mod error;
 mod responses;
+mod trace;
+mod transport;
+
+use commands::{
+    Command, DeleteRequest, Header, ListDirRequest, MkdirRequest, MoveRequest, ReadPacingRequest,
+    ReadRequest, WriteData, WriteHeader,
+};
 use responses::*;
 
-pub struct InfiniTime {
-    peripheral: Peripheral,
-    notifications: BoxStream<'static, ValueNotification>,
-    version_c: Characteristic,
-    transfer_c: Characteristic,
+pub use error::{FtpError, Result};
+pub use trace::{TraceEntry, TraceEvent, TraceLog};
+pub use transport::{BtleTransport, Transport};
+
+pub struct InfiniTime<T> {
+    transport: T,
+    trace: Option<TraceLog>,
 }
 
-const MAX_PAYLOAD: u32 = 0xE7;
-const VERSION: Uuid = uuid!("adaf0100-4669-6c65-5472-616e73666572");
-const TRANSFER: Uuid = uuid!("adaf0200-4669-6c65-5472-616e73666572");
-
-impl InfiniTime {
-    pub async fn new(peripheral: Peripheral) -> Result<Self> {
-        peripheral.connect().await?;
-        peripheral.discover_services().await?;
-
-        let notifications = peripheral.notifications().await?;
-
-        let characteristics = peripheral.characteristics();
-        let version_c = characteristics
-            .iter()
-            .find(|c| c.uuid == VERSION)
-            .expect("Could not find version characteristic")
-            .clone();
-
-        let transfer_c = characteristics
-            .iter()
-            .find(|c| c.uuid == TRANSFER)
-            .expect("Could not find transfer characteristic")
-            .clone();
-
-        peripheral.subscribe(&transfer_c).await?;
-
-        Ok(Self {
-            peripheral,
-            notifications,
-            version_c,
-            transfer_c,
-        })
+impl<T> InfiniTime<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            trace: None,
+        }
+    }
+
+    /// Like [`Self::new`], but keeps a bounded in-memory log of the last
+    /// `capacity` protocol frames for diagnosing stalls after the fact - see
+    /// [`Self::trace_snapshot`] and [`Self::drain_trace`].
+    pub fn with_trace(transport: T, capacity: usize) -> Self {
+        Self {
+            transport,
+            trace: Some(TraceLog::new(capacity)),
+        }
+    }
+
+    /// Copy out the frames currently held in the trace log, if tracing is
+    /// enabled, without clearing it.
+    pub fn trace_snapshot(&self) -> Option<Vec<TraceEntry>> {
+        self.trace.as_ref().map(TraceLog::snapshot)
+    }
+
+    /// Drain and return the frames currently held in the trace log, if
+    /// tracing is enabled.
+    pub fn drain_trace(&mut self) -> Option<Vec<TraceEntry>> {
+        self.trace.as_mut().map(TraceLog::drain)
+    }
+}
+
+impl InfiniTime<BtleTransport> {
+    pub async fn connect(peripheral: Peripheral) -> Result<Self> {
+        Ok(Self::new(BtleTransport::new(peripheral).await?))
     }
 
     pub async fn version(&self) -> Result<u32> {
-        let mut bytes = self.peripheral.read(&self.version_c).await?;
-        bytes.resize(std::mem::size_of::<u32>(), 0_u8);
-        let four_bytes = bytes.try_into().unwrap();
-        Ok(u32::from_le_bytes(four_bytes))
+        self.transport.version().await
     }
+}
 
+impl<T: Transport> InfiniTime<T> {
     async fn send(&self, f: impl FnOnce(&mut Vec<u8>)) -> Result<()> {
         let mut buf = Vec::new();
         f(&mut buf);
-        self.peripheral
-            .write(&self.transfer_c, &buf, WriteType::WithoutResponse)
-            .await?;
+        self.transport.write(&buf).await?;
         Ok(())
     }
 
-    async fn recv<T: responses::Body>(&mut self) -> Option<responses::Response<T>> {
-        let notif = self.notifications.next().await?;
-        let response: &responses::Response<T> = bytemuck::from_bytes(&notif.value);
+    /// Serialize a command's fixed-size header followed by its
+    /// variable-length tail (a path, or write data) and send it as one
+    /// frame.
+    async fn send_command<C: Command>(&mut self, body: C, tail: &[u8]) -> Result<()> {
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEvent::Sent {
+                command: C::COMMAND,
+                len: std::mem::size_of::<Header<C>>() + tail.len(),
+                offset: body.trace_offset(),
+            });
+        }
 
-        assert_eq!(response.command, T::COMMAND);
-        assert_eq!(response.status, 1, "bad status");
+        let header = Header::new(body);
+        self.send(|buf| {
+            buf.extend(bytemuck::bytes_of(&header));
+            buf.extend(tail);
+        })
+        .await
+    }
 
-        Some(*response)
+    async fn recv<B: responses::Body>(&mut self) -> Result<responses::Response<B>> {
+        let value = self
+            .transport
+            .next_notification()
+            .await
+            .ok_or(FtpError::Truncated)?;
+        let response: &responses::Response<B> = bytemuck::from_bytes(&value);
+
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEvent::Received {
+                command: response.command,
+                status: response.status,
+                payload_len: 0,
+            });
+        }
+
+        if response.command != B::COMMAND {
+            return Err(FtpError::UnexpectedCommand {
+                expected: B::COMMAND,
+                got: response.command,
+            });
+        }
+        FtpError::check_status(response.status)?;
+
+        Ok(*response)
     }
 
-    async fn payload_recv<T: responses::Body>(
+    async fn payload_recv<B: responses::Body>(
         &mut self,
-    ) -> Option<(responses::Response<T>, Vec<u8>)> {
-        let notif = self.notifications.next().await?;
-        let mut data = notif.value;
-        let payload = data.split_off(std::mem::size_of::<T>());
-
-        let response: &responses::Response<T> = bytemuck::from_bytes(&data);
+    ) -> Result<(responses::Response<B>, Vec<u8>)> {
+        let mut data = self
+            .transport
+            .next_notification()
+            .await
+            .ok_or(FtpError::Truncated)?;
+        let payload = data.split_off(std::mem::size_of::<B>());
+
+        let response: &responses::Response<B> = bytemuck::from_bytes(&data);
+
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEvent::Received {
+                command: response.command,
+                status: response.status,
+                payload_len: payload.len(),
+            });
+        }
 
-        assert_eq!(response.command, T::COMMAND);
-        assert_eq!(response.status, 1, "bad status");
+        if response.command != B::COMMAND {
+            return Err(FtpError::UnexpectedCommand {
+                expected: B::COMMAND,
+                got: response.command,
+            });
+        }
+        FtpError::check_status(response.status)?;
 
-        Some((*response, payload))
+        Ok((*response, payload))
     }
 
     pub async fn list_dir(&mut self, path: &str) -> Result<Vec<DirEntry>> {
-        self.send(|buf| {
-            buf.push(0x50);
-            buf.push(0);
-            buf.extend((path.len() as u16).to_le_bytes());
-            buf.extend(path.as_bytes());
-        })
-        .await?;
+        self.send_command(ListDirRequest::new(path.len() as u16), path.as_bytes())
+            .await?;
 
         let mut entries = vec![];
 
-        while let Some((raw, path)) = self.payload_recv::<RawDirEntry>().await {
+        loop {
+            let (raw, path) = self.payload_recv::<RawDirEntry>().await?;
             assert_eq!(raw.body.entry_number as usize, entries.len());
             assert_eq!(raw.body.path_len as usize, path.len());
 
@@ -125,38 +179,36 @@ impl InfiniTime {
     }
 
     pub async fn read_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        let max_payload = self.transport.max_payload();
         let mut offset = 0_u32;
 
-        self.send(|buf| {
-            buf.push(0x10);
-            buf.push(0);
-            buf.extend((path.len() as u16).to_le_bytes());
-            buf.extend(offset.to_le_bytes());
-            buf.extend(MAX_PAYLOAD.to_le_bytes());
-            buf.extend(path.as_bytes());
-        })
+        self.send_command(
+            ReadRequest::new(path.len() as u16, offset, max_payload),
+            path.as_bytes(),
+        )
         .await?;
 
         let mut contents = Vec::new();
 
-        while let Some((response, payload)) = self.payload_recv::<FileChunk>().await {
+        loop {
+            let (response, payload) = self.payload_recv::<FileChunk>().await?;
             assert_eq!({ response.body.offset }, offset);
             assert_eq!(response.body.current_len as usize, payload.len());
 
             contents.extend(payload);
+            offset += response.body.current_len;
+
             if contents.len() == response.body.total_len as usize {
                 break;
             }
 
-            offset += response.body.current_len;
-            self.send(|buf| {
-                buf.push(0x12);
-                buf.push(0x01);
-                buf.extend([0, 0]);
-                buf.extend(offset.to_le_bytes());
-                buf.extend(MAX_PAYLOAD.to_le_bytes());
-            })
-            .await?;
+            // The watch is stop-and-wait on reads: it sends exactly one
+            // FileChunk per pacing request, no matter how large a window we
+            // ask for. Re-request after every chunk instead of waiting for
+            // a window's worth to arrive unprompted, which would never
+            // happen.
+            self.send_command(ReadPacingRequest::new(offset, max_payload), &[])
+                .await?;
         }
 
         Ok(contents)
@@ -168,91 +220,89 @@ impl InfiniTime {
         data: &[u8],
         timestamp: impl Timestamp,
     ) -> Result<()> {
+        let max_payload = self.transport.max_payload();
         let mut offset = 0_u32;
 
-        self.send(|buf| {
-            buf.push(0x20);
-            buf.push(0);
-            buf.extend((path.len() as u16).to_le_bytes());
-            buf.extend(offset.to_le_bytes());
-            buf.extend(timestamp.to_u64().to_le_bytes());
-            buf.extend((data.len() as u32).to_le_bytes());
-            buf.extend(path.as_bytes());
-        })
+        self.send_command(
+            WriteHeader::new(
+                path.len() as u16,
+                offset,
+                timestamp.to_u64(),
+                data.len() as u32,
+            ),
+            path.as_bytes(),
+        )
         .await?;
 
-        while let Some(_response) = self.recv::<WriteReceipt>().await {
+        loop {
+            let response = self.recv::<WriteReceipt>().await?;
             // assert_eq!({ response.body.offset }, offset);
 
-            let mut remaining_data = &data[offset as usize..];
-
-            if remaining_data.len() > MAX_PAYLOAD as usize {
-                remaining_data = &remaining_data[..MAX_PAYLOAD as usize];
+            // The watch just told us how much free space it's ready to
+            // accept; keep firing back-to-back WithoutResponse packets until
+            // we've filled that window instead of waiting for a receipt per
+            // chunk.
+            let mut window_remaining = response.body.remaining;
+
+            while window_remaining > 0 && (offset as usize) < data.len() {
+                let mut chunk = &data[offset as usize..];
+
+                if chunk.len() as u32 > window_remaining {
+                    chunk = &chunk[..window_remaining as usize];
+                }
+                if chunk.len() > max_payload as usize {
+                    chunk = &chunk[..max_payload as usize];
+                }
+                if chunk.is_empty() {
+                    break;
+                }
+
+                self.send_command(WriteData::new(offset, chunk.len() as u32), chunk)
+                    .await?;
+
+                offset += chunk.len() as u32;
+                window_remaining -= chunk.len() as u32;
             }
 
-            if remaining_data.is_empty() {
+            if offset as usize >= data.len() {
                 break;
             }
-
-            self.send(|buf| {
-                buf.push(0x22);
-                buf.push(1);
-                buf.extend([0, 0]);
-                buf.extend(offset.to_le_bytes());
-                buf.extend((remaining_data.len() as u32).to_le_bytes());
-                buf.extend(remaining_data);
-            })
-            .await?;
-
-            offset += remaining_data.len() as u32;
         }
 
         Ok(())
     }
 
     pub async fn delete_file(&mut self, path: &str) -> Result<()> {
-        self.send(|buf| {
-            buf.push(0x30);
-            buf.push(0);
-            buf.extend((path.len() as u16).to_le_bytes());
-            buf.extend(path.as_bytes());
-        })
-        .await?;
+        self.send_command(DeleteRequest::new(path.len() as u16), path.as_bytes())
+            .await?;
 
-        self.recv::<RmReceipt>().await.unwrap();
+        self.recv::<RmReceipt>().await?;
 
         Ok(())
     }
 
     pub async fn create_dir(&mut self, path: &str, timestamp: impl Timestamp) -> Result<()> {
-        self.send(|buf| {
-            buf.push(0x40);
-            buf.push(0);
-            buf.extend((path.len() as u16).to_le_bytes());
-            buf.extend([0; 4]);
-            buf.extend(timestamp.to_u64().to_le_bytes());
-            buf.extend(path.as_bytes());
-        })
+        self.send_command(
+            MkdirRequest::new(path.len() as u16, timestamp.to_u64()),
+            path.as_bytes(),
+        )
         .await?;
 
-        self.recv::<MkdirReceipt>().await.unwrap();
+        self.recv::<MkdirReceipt>().await?;
 
         Ok(())
     }
 
     pub async fn move_file(&mut self, from: &str, to: &str) -> Result<()> {
-        self.send(|buf| {
-            buf.push(0x60);
-            buf.push(0);
-            buf.extend((from.len() as u16).to_le_bytes());
-            buf.extend((to.len() as u16).to_le_bytes());
-            buf.extend(from.as_bytes());
-            buf.push(0);
-            buf.extend(to.as_bytes());
-        })
-        .await?;
+        let mut tail = Vec::with_capacity(from.len() + 1 + to.len());
+        tail.extend(from.as_bytes());
+        tail.push(0);
+        tail.extend(to.as_bytes());
+
+        self.send_command(MoveRequest::new(from.len() as u16, to.len() as u16), &tail)
+            .await?;
 
-        self.recv::<MvReceipt>().await.unwrap();
+        self.recv::<MvReceipt>().await?;
 
         Ok(())
     }
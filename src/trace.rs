@@ -0,0 +1,66 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// A single recorded frame: either a command we sent or a notification the
+/// watch sent back.
+#[derive(Debug, Clone, Copy)]
+pub enum TraceEvent {
+    Sent {
+        command: u8,
+        len: usize,
+        offset: Option<u32>,
+    },
+    Received {
+        command: u8,
+        status: i8,
+        payload_len: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEntry {
+    pub timestamp: SystemTime,
+    pub event: TraceEvent,
+}
+
+/// A bounded ring buffer of recent protocol frames, kept around so a caller
+/// can dump the last few exchanges after a stalled transfer or an error.
+/// Never grows past `capacity` entries; the oldest entry is dropped to make
+/// room for the newest.
+pub struct TraceLog {
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl TraceLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn push(&mut self, event: TraceEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry {
+            timestamp: SystemTime::now(),
+            event,
+        });
+    }
+
+    /// Copy out the entries currently held, oldest first, without clearing
+    /// the log.
+    pub fn snapshot(&self) -> Vec<TraceEntry> {
+        self.entries.iter().copied().collect()
+    }
+
+    /// Remove and return all buffered entries, oldest first.
+    pub fn drain(&mut self) -> Vec<TraceEntry> {
+        self.entries.drain(..).collect()
+    }
+}
@@ -0,0 +1,217 @@
+use std::future::Future;
+
+use btleplug::api::{Characteristic, Peripheral as _, ValueNotification, WriteType};
+use btleplug::platform::Peripheral;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use uuid::{uuid, Uuid};
+
+use crate::Result;
+
+const VERSION: Uuid = uuid!("adaf0100-4669-6c65-5472-616e73666572");
+const TRANSFER: Uuid = uuid!("adaf0200-4669-6c65-5472-616e73666572");
+
+/// Bytes of ATT protocol overhead (opcode + attribute handle) subtracted
+/// from the negotiated MTU to get the payload actually usable by a write or
+/// notification.
+const ATT_OVERHEAD: u32 = 3;
+
+/// btleplug 0.11 has no API to read back the ATT MTU a connection actually
+/// negotiated (checked against its public surface; nothing like it exists),
+/// so absent a value supplied some other way we fall back to the effective
+/// payload size (231 bytes, i.e. a 234-byte MTU) this client has always
+/// assumed.
+const DEFAULT_MTU: u32 = 234;
+
+/// The byte pipe the Adafruit File-Transfer protocol is framed over.
+///
+/// [`crate::InfiniTime`] only ever writes whole frames and waits for whole
+/// notification frames back, so any link that can do that - a serial or TCP
+/// bridge, an in-memory mock for tests - can stand in for [`BtleTransport`].
+///
+/// Methods return `impl Future` rather than being declared `async fn` so
+/// that the futures are bound `Send`, matching what [`crate::InfiniTime`]
+/// needs to be usable from a multi-threaded executor.
+pub trait Transport {
+    /// Write a single frame.
+    fn write(&self, data: &[u8]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Wait for the next incoming notification frame, or `None` once the
+    /// underlying link has closed.
+    fn next_notification(&mut self) -> impl Future<Output = Option<Vec<u8>>> + Send;
+
+    /// The largest payload a single write or notification can carry on this
+    /// link, so callers can size requests to the actual transport capacity
+    /// instead of assuming a fixed packet size.
+    fn max_payload(&self) -> u32;
+}
+
+/// The real transport: the transfer characteristic of a BLE peripheral
+/// speaking the Adafruit File-Transfer service, accessed through btleplug.
+pub struct BtleTransport {
+    peripheral: Peripheral,
+    notifications: BoxStream<'static, ValueNotification>,
+    version_c: Characteristic,
+    transfer_c: Characteristic,
+    max_payload: u32,
+}
+
+impl BtleTransport {
+    /// Connect to `peripheral` assuming the default, conservative MTU.
+    ///
+    /// Equivalent to [`Self::with_mtu`]`(peripheral, None)` - see that
+    /// constructor for why this crate can't negotiate the MTU itself.
+    pub async fn new(peripheral: Peripheral) -> Result<Self> {
+        Self::with_mtu(peripheral, None).await
+    }
+
+    /// Connect to `peripheral`, sizing writes and notifications for `mtu`
+    /// bytes instead of the conservative default.
+    ///
+    /// btleplug 0.11's [`Peripheral`] trait has no method to read back the
+    /// ATT MTU a connection negotiated, so this crate cannot determine it on
+    /// its own. If the caller has obtained the real negotiated MTU some
+    /// other way - a platform-specific API, a value logged by the OS
+    /// Bluetooth stack, or prior knowledge of the peer - pass it here to
+    /// avoid the undersized default. Otherwise use [`Self::new`], which
+    /// keeps assuming the 234-byte MTU this client has always used.
+    pub async fn with_mtu(peripheral: Peripheral, mtu: Option<u32>) -> Result<Self> {
+        peripheral.connect().await?;
+        peripheral.discover_services().await?;
+
+        let notifications = peripheral.notifications().await?;
+
+        let characteristics = peripheral.characteristics();
+        let version_c = characteristics
+            .iter()
+            .find(|c| c.uuid == VERSION)
+            .expect("Could not find version characteristic")
+            .clone();
+
+        let transfer_c = characteristics
+            .iter()
+            .find(|c| c.uuid == TRANSFER)
+            .expect("Could not find transfer characteristic")
+            .clone();
+
+        peripheral.subscribe(&transfer_c).await?;
+
+        let max_payload = mtu.unwrap_or(DEFAULT_MTU).saturating_sub(ATT_OVERHEAD);
+
+        Ok(Self {
+            peripheral,
+            notifications,
+            version_c,
+            transfer_c,
+            max_payload,
+        })
+    }
+
+    pub async fn version(&self) -> Result<u32> {
+        let mut bytes = self.peripheral.read(&self.version_c).await?;
+        bytes.resize(std::mem::size_of::<u32>(), 0_u8);
+        let four_bytes = bytes.try_into().unwrap();
+        Ok(u32::from_le_bytes(four_bytes))
+    }
+}
+
+impl Transport for BtleTransport {
+    fn write(&self, data: &[u8]) -> impl Future<Output = Result<()>> + Send {
+        // Clone the owned handles rather than borrowing `self` so the
+        // returned future doesn't need `BtleTransport: Sync` to be `Send`.
+        let peripheral = self.peripheral.clone();
+        let transfer_c = self.transfer_c.clone();
+        let data = data.to_vec();
+        async move {
+            peripheral
+                .write(&transfer_c, &data, WriteType::WithoutResponse)
+                .await?;
+            Ok(())
+        }
+    }
+
+    async fn next_notification(&mut self) -> Option<Vec<u8>> {
+        Some(self.notifications.next().await?.value)
+    }
+
+    fn max_payload(&self) -> u32 {
+        self.max_payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// An in-memory [`Transport`] for exercising [`crate::InfiniTime`]
+    /// without a real BLE link: writes are recorded for inspection, and
+    /// queued frames are handed back as notifications in order.
+    ///
+    /// Cloning shares the same underlying state, so a test can hand one
+    /// clone to [`crate::InfiniTime`] and keep the other to inspect what
+    /// was written.
+    #[derive(Clone)]
+    pub struct MockTransport {
+        max_payload: u32,
+        written: Arc<Mutex<Vec<Vec<u8>>>>,
+        incoming: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    }
+
+    impl MockTransport {
+        pub fn new(max_payload: u32) -> Self {
+            Self {
+                max_payload,
+                written: Arc::new(Mutex::new(Vec::new())),
+                incoming: Arc::new(Mutex::new(VecDeque::new())),
+            }
+        }
+
+        pub fn queue_notification(&self, frame: Vec<u8>) {
+            self.incoming.lock().unwrap().push_back(frame);
+        }
+
+        pub fn writes(&self) -> Vec<Vec<u8>> {
+            self.written.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn write(&self, data: &[u8]) -> impl Future<Output = Result<()>> + Send {
+            self.written.lock().unwrap().push(data.to_vec());
+            async { Ok(()) }
+        }
+
+        fn next_notification(&mut self) -> impl Future<Output = Option<Vec<u8>>> + Send {
+            let frame = self.incoming.lock().unwrap().pop_front();
+            async move { frame }
+        }
+
+        fn max_payload(&self) -> u32 {
+            self.max_payload
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_file_round_trip() {
+        use crate::InfiniTime;
+
+        let transport = MockTransport::new(DEFAULT_MTU - ATT_OVERHEAD);
+        let response = crate::responses::Response {
+            command: 0x31,
+            status: 1,
+            body: crate::responses::RmReceipt,
+        };
+        transport.queue_notification(bytemuck::bytes_of(&response).to_vec());
+
+        let mut watch = InfiniTime::new(transport.clone());
+        watch.delete_file("/test").await.unwrap();
+
+        let writes = transport.writes();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0][0], 0x30);
+        assert_eq!(&writes[0][4..], b"/test");
+    }
+}